@@ -1,6 +1,7 @@
 use super::types;
 use crate::common::types as common;
 use crate::syntax::ast;
+use std::cmp::Ordering;
 
 #[derive(Fail, Debug, Clone, PartialEq, Eq)]
 pub enum ParseError {
@@ -12,10 +13,37 @@ pub enum ParseError {
     NotAggregateFunction,
     #[fail(display = "Select Expression Must Be Named")]
     SelectExprMustBeNamed,
+    #[fail(display = "DISTINCT * Is Only Allowed For count")]
+    DistinctStarNotAllowed,
+    #[fail(display = "{} parse error(s)", "_0.len()")]
+    Diagnostics(Vec<Diagnostic>),
 }
 
 pub type ParseResult<T> = Result<T, ParseError>;
 
+/// The clause a diagnostic originated from, so several problems in one query can
+/// be reported against the right position.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Clause {
+    Select(usize),
+    Where,
+    Having,
+    OrderBy,
+}
+
+/// A single parse failure tagged with the clause that produced it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub clause: Clause,
+    pub error: ParseError,
+}
+
+impl Diagnostic {
+    fn new(clause: Clause, error: ParseError) -> Diagnostic {
+        Diagnostic { clause, error }
+    }
+}
+
 fn parse_prefix_operator(op: types::LogicPrefixOp, child: &ast::Expression) -> ParseResult<Box<types::Formula>> {
     let child_parsed = parse_logic(child)?;
 
@@ -66,6 +94,7 @@ fn parse_value(value: &ast::Value) -> ParseResult<Box<types::Expression>> {
         ast::Value::Float(f) => Ok(Box::new(types::Expression::Constant(common::Value::Float(*f)))),
         ast::Value::Integral(i) => Ok(Box::new(types::Expression::Constant(common::Value::Int(*i)))),
         ast::Value::StringLiteral(s) => Ok(Box::new(types::Expression::Constant(common::Value::String(s.clone())))),
+        ast::Value::Null => Ok(Box::new(types::Expression::Constant(common::Value::Null))),
     }
 }
 
@@ -95,7 +124,10 @@ fn parse_value_expression(value_expr: &ast::ValueExpression) -> ParseResult<Box<
         }
         ast::ValueExpression::Column(column_name) => Ok(Box::new(types::Expression::Variable(column_name.clone()))),
         ast::ValueExpression::Operator(_, _, _) => parse_arithemetic(value_expr),
-        ast::ValueExpression::FuncCall(func_name, select_exprs, within_group_opt) => {
+        ast::ValueExpression::FuncCall(func_name, _distinct, select_exprs, _within_group_opt) => {
+            if func_name.eq_ignore_ascii_case("coalesce") {
+                return parse_coalesce(select_exprs);
+            }
             let mut args = Vec::new();
             for select_expr in select_exprs.iter() {
                 let arg = parse_expression(select_expr)?;
@@ -103,7 +135,77 @@ fn parse_value_expression(value_expr: &ast::ValueExpression) -> ParseResult<Box<
             }
             Ok(Box::new(types::Expression::Function(func_name.clone(), args)))
         }
+        ast::ValueExpression::Case(branches, else_opt) => {
+            let mut parsed_branches = Vec::with_capacity(branches.len());
+            for (condition, result) in branches.iter() {
+                let condition = parse_logic(condition)?;
+                let result = parse_value_expression(result)?;
+                parsed_branches.push((condition, result));
+            }
+            let else_opt = match else_opt {
+                Some(else_expr) => Some(parse_value_expression(else_expr)?),
+                None => None,
+            };
+            Ok(Box::new(types::Expression::Case(parsed_branches, else_opt)))
+        }
+    }
+}
+
+// COALESCE(a, b, ...) evaluates to its first non-null argument. Rather than a
+// bespoke runtime op we lower it onto the existing CASE machinery:
+//   CASE WHEN a IS NOT NULL THEN a WHEN b IS NOT NULL THEN b ... ELSE <last> END
+// so evaluation handles it with no COALESCE-specific code. The constant-folding
+// pass leaves CASE expressions as they are.
+fn parse_coalesce(args: &[ast::SelectExpression]) -> ParseResult<Box<types::Expression>> {
+    let mut exprs = Vec::with_capacity(args.len());
+    for arg in args.iter() {
+        match *parse_expression(arg)? {
+            types::Named::Expression(expr, _) => exprs.push(Box::new(expr)),
+            _ => return Err(ParseError::TypeMismatch),
+        }
+    }
+
+    // The final argument is the fall-through: if every prior argument was null
+    // the CASE has no matching branch and returns it (null when absent).
+    let else_opt = exprs.pop();
+    let mut branches = Vec::with_capacity(exprs.len());
+    for expr in exprs.into_iter() {
+        let is_not_null = Box::new(types::Formula::IsNull(expr.clone(), true));
+        branches.push((is_not_null, expr));
     }
+    Ok(Box::new(types::Expression::Case(branches, else_opt)))
+}
+
+fn parse_sort_direction(direction: &ast::SortDirection) -> types::SortDirection {
+    match direction {
+        ast::SortDirection::Asc => types::SortDirection::Asc,
+        ast::SortDirection::Desc => types::SortDirection::Desc,
+    }
+}
+
+// The AST does not yet surface an explicit NULLS FIRST/LAST, so each key takes
+// the SQL-standard default for its direction: nulls sort last under ASC and
+// first under DESC. Threading it per key (rather than relying on a single global
+// `common::Value::Ord` rule) keeps the door open for an explicit clause later.
+fn default_nulls_order(direction: &types::SortDirection) -> types::NullsOrder {
+    match direction {
+        types::SortDirection::Asc => types::NullsOrder::Last,
+        types::SortDirection::Desc => types::NullsOrder::First,
+    }
+}
+
+// Each key carries its own direction and null-ordering rule.
+fn parse_order_by(
+    order_by: &ast::OrderByExpression,
+) -> ParseResult<Vec<(types::Expression, types::SortDirection, types::NullsOrder)>> {
+    let mut keys = Vec::with_capacity(order_by.exprs.len());
+    for (value_expr, direction) in order_by.exprs.iter() {
+        let expr = parse_value_expression(value_expr)?;
+        let direction = parse_sort_direction(direction);
+        let nulls_order = default_nulls_order(&direction);
+        keys.push((*expr, direction, nulls_order));
+    }
+    Ok(keys)
 }
 
 fn parse_relation(op: &ast::RelationOperator) -> ParseResult<types::Relation> {
@@ -125,6 +227,10 @@ fn parse_condition(condition: &ast::Condition) -> ParseResult<Box<types::Formula
             let rel_op = parse_relation(op)?;
             Ok(Box::new(types::Formula::Predicate(rel_op, left, right)))
         }
+        ast::Condition::IsNull(value_expr, negated) => {
+            let expr = parse_value_expression(value_expr)?;
+            Ok(Box::new(types::Formula::IsNull(expr, *negated)))
+        }
     }
 }
 
@@ -163,26 +269,82 @@ fn parse_expression(select_expr: &ast::SelectExpression) -> ParseResult<Box<type
     }
 }
 
-fn from_str(value: &str, named: types::Named) -> ParseResult<types::Aggregate> {
+fn from_str(value: &str, named: types::Named, distinct: bool) -> ParseResult<types::Aggregate> {
+    // `*` is only meaningful as a row counter; anything else aggregating over
+    // every column (let alone a DISTINCT one) is a type error.
+    if let types::Named::Star = named {
+        if value != "count" {
+            return Err(ParseError::TypeMismatch);
+        }
+    }
+
     match value {
-        "avg" => Ok(types::Aggregate::Avg(named)),
-        "count" => Ok(types::Aggregate::Count(named)),
-        "first" => Ok(types::Aggregate::First(named)),
-        "last" => Ok(types::Aggregate::Last(named)),
-        "max" => Ok(types::Aggregate::Max(named)),
-        "min" => Ok(types::Aggregate::Min(named)),
-        "sum" => Ok(types::Aggregate::Sum(named)),
+        "avg" => Ok(types::Aggregate::Avg(named, distinct)),
+        "count" => Ok(types::Aggregate::Count(named, distinct)),
+        "first" => Ok(types::Aggregate::First(named, distinct)),
+        "last" => Ok(types::Aggregate::Last(named, distinct)),
+        "max" => Ok(types::Aggregate::Max(named, distinct)),
+        "min" => Ok(types::Aggregate::Min(named, distinct)),
+        "sum" => Ok(types::Aggregate::Sum(named, distinct)),
         _ => Err(ParseError::NotAggregateFunction),
     }
 }
 
+// GROUP_CONCAT takes an optional second argument naming the separator string.
+fn parse_group_concat_separator(args: &[ast::SelectExpression]) -> Option<String> {
+    match args.get(1) {
+        Some(ast::SelectExpression::Expression(expr, _)) => match &**expr {
+            ast::Expression::Value(value_expr) => match &**value_expr {
+                ast::ValueExpression::Value(ast::Value::StringLiteral(s)) => Some(s.clone()),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Every aggregate wraps exactly one `Named` input; pull it out regardless of
+// the variant so GROUP BY planning can project the column uniformly.
+fn aggregate_named(aggregate: &types::Aggregate) -> types::Named {
+    match aggregate {
+        types::Aggregate::Avg(named, _)
+        | types::Aggregate::Count(named, _)
+        | types::Aggregate::First(named, _)
+        | types::Aggregate::Last(named, _)
+        | types::Aggregate::Max(named, _)
+        | types::Aggregate::Min(named, _)
+        | types::Aggregate::Sum(named, _)
+        | types::Aggregate::GroupConcat(named, _, _) => named.clone(),
+    }
+}
+
 fn parse_aggregate(select_expr: &ast::SelectExpression) -> ParseResult<types::NamedAggregate> {
     match select_expr {
         ast::SelectExpression::Expression(expr, name_opt) => match &**expr {
             ast::Expression::Value(value_expr) => match &**value_expr {
-                ast::ValueExpression::FuncCall(func_name, args, within_group_opt) => {
+                ast::ValueExpression::FuncCall(func_name, distinct, args, _within_group_opt) => {
                     let named = *parse_expression(&args[0])?;
-                    let aggregate = from_str(&**func_name, named)?;
+
+                    // `DISTINCT *` is nonsensical for every aggregate but count.
+                    if *distinct {
+                        if let types::Named::Star = named {
+                            if func_name.as_str() != "count" {
+                                return Err(ParseError::DistinctStarNotAllowed);
+                            }
+                        }
+                    }
+
+                    let aggregate = if func_name.as_str() == "group_concat" {
+                        if let types::Named::Star = named {
+                            return Err(ParseError::TypeMismatch);
+                        }
+                        let separator = parse_group_concat_separator(args);
+                        types::Aggregate::GroupConcat(named, *distinct, separator)
+                    } else {
+                        from_str(&**func_name, named, *distinct)?
+                    };
+
                     let named_aggregate = types::NamedAggregate::new(aggregate, name_opt.clone());
                     Ok(named_aggregate)
                 }
@@ -195,29 +357,30 @@ fn parse_aggregate(select_expr: &ast::SelectExpression) -> ParseResult<types::Na
 }
 
 pub(crate) fn parse_query(query: ast::SelectStatement, data_source: common::DataSource) -> ParseResult<types::Node> {
+    // Walk the whole statement like a static analyzer: collect every clause's
+    // failure into `diagnostics` (a failed select expression is replaced by a
+    // placeholder projection and a failed WHERE/HAVING/ORDER BY clause is left
+    // out, so planning can continue) and only fail once, so a query with several
+    // mistakes surfaces them all in one pass.
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
     let mut root = types::Node::DataSource(data_source);
 
     let mut named_aggregates = Vec::new();
     if !query.select_exprs.is_empty() {
         let mut named_list: Vec<types::Named> = Vec::new();
-        for select_expr in query.select_exprs.iter() {
-            let parse_aggregate_result = parse_aggregate(select_expr);
-            if parse_aggregate_result.is_ok() {
-                let named_aggregate = parse_aggregate_result.unwrap();
-                named_aggregates.push(named_aggregate.clone());
-
-                match named_aggregate.aggregate {
-                    types::Aggregate::Avg(named) => {
-                        named_list.push(named);
-                    }
-                    types::Aggregate::Count(named) => {
-                        named_list.push(named);
-                    }
-                    _ => unimplemented!(),
+        for (index, select_expr) in query.select_exprs.iter().enumerate() {
+            match parse_aggregate(select_expr) {
+                Ok(named_aggregate) => {
+                    named_list.push(aggregate_named(&named_aggregate.aggregate));
+                    named_aggregates.push(named_aggregate);
                 }
-            } else {
-                let named = *parse_expression(select_expr)?;
-                named_list.push(named);
+                Err(_) => match parse_expression(select_expr) {
+                    Ok(named) => named_list.push(*named),
+                    Err(error) => {
+                        diagnostics.push(Diagnostic::new(Clause::Select(index), error));
+                        named_list.push(placeholder_named());
+                    }
+                },
             }
         }
 
@@ -225,20 +388,246 @@ pub(crate) fn parse_query(query: ast::SelectStatement, data_source: common::Data
     }
 
     if let Some(where_expr) = query.where_expr_opt {
-        let filter_formula = parse_logic(&where_expr.expr)?;
-        root = types::Node::Filter(filter_formula, Box::new(root));
+        match parse_logic(&where_expr.expr) {
+            Ok(filter_formula) => root = types::Node::Filter(filter_formula, Box::new(root)),
+            Err(error) => diagnostics.push(Diagnostic::new(Clause::Where, error)),
+        }
     }
 
     if let Some(group_by) = query.group_by_exprs_opt {
         let fields = group_by.exprs.clone();
-        root = types::Node::GroupBy(fields, named_aggregates, Box::new(root));
+        // Only HAVING needs a second copy of the aggregates; without it GroupBy is
+        // the last consumer and can take ownership.
+        let aggregates = if query.having_expr_opt.is_some() {
+            named_aggregates.clone()
+        } else {
+            std::mem::take(&mut named_aggregates)
+        };
+        root = types::Node::GroupBy(fields, aggregates, Box::new(root));
+    }
+
+    // HAVING filters the aggregated rows, so it sits above the GroupBy and is
+    // resolved against its projected schema (aggregate names/aliases), not the
+    // raw source columns.
+    if let Some(having_expr) = query.having_expr_opt {
+        match parse_logic(&having_expr.expr) {
+            Ok(having_formula) => root = types::Node::Having(having_formula, named_aggregates, Box::new(root)),
+            Err(error) => diagnostics.push(Diagnostic::new(Clause::Having, error)),
+        }
+    }
+
+    // ORDER BY sits below LIMIT so that the row cap is applied to the sorted
+    // output ("top N slowest requests").
+    if let Some(order_by) = query.order_by_exprs_opt {
+        match parse_order_by(&order_by) {
+            Ok(keys) => root = types::Node::OrderBy(keys, Box::new(root)),
+            Err(error) => diagnostics.push(Diagnostic::new(Clause::OrderBy, error)),
+        }
     }
 
     if let Some(limit_expr) = query.limit_expr_opt {
         root = types::Node::Limit(limit_expr.row_count, Box::new(root));
     }
 
-    Ok(root)
+    if !diagnostics.is_empty() {
+        return Err(ParseError::Diagnostics(diagnostics));
+    }
+
+    Ok(optimize(root))
+}
+
+// Stand-in projection inserted where a select expression failed to parse, so the
+// planner can keep collecting diagnostics from the remaining clauses.
+fn placeholder_named() -> types::Named {
+    types::Named::Expression(types::Expression::Constant(common::Value::Null), None)
+}
+
+// Constant-folding / partial-evaluation pass. Runs bottom-up over the planned
+// node tree, collapsing any `Expression`/`Formula` subtree whose inputs are all
+// constants and applying the usual boolean short-circuit simplifications. Nodes
+// referencing a `Variable` (or an aggregate) are left untouched because their
+// value depends on the row being evaluated.
+fn optimize(node: types::Node) -> types::Node {
+    match node {
+        types::Node::Filter(formula, child) => {
+            types::Node::Filter(fold_formula(formula), Box::new(optimize(*child)))
+        }
+        types::Node::Map(named_list, child) => {
+            let folded = named_list.into_iter().map(fold_named).collect();
+            types::Node::Map(folded, Box::new(optimize(*child)))
+        }
+        types::Node::Having(formula, named_aggregates, child) => {
+            types::Node::Having(fold_formula(formula), named_aggregates, Box::new(optimize(*child)))
+        }
+        types::Node::GroupBy(fields, named_aggregates, child) => {
+            types::Node::GroupBy(fields, named_aggregates, Box::new(optimize(*child)))
+        }
+        types::Node::OrderBy(keys, child) => types::Node::OrderBy(keys, Box::new(optimize(*child))),
+        types::Node::Limit(row_count, child) => types::Node::Limit(row_count, Box::new(optimize(*child))),
+        other => other,
+    }
+}
+
+fn fold_named(named: types::Named) -> types::Named {
+    match named {
+        types::Named::Expression(expr, name_opt) => types::Named::Expression(fold_expression(expr), name_opt),
+        other => other,
+    }
+}
+
+fn fold_expression(expr: types::Expression) -> types::Expression {
+    match expr {
+        types::Expression::Function(op, args) => {
+            let folded_args: Vec<types::Named> = args.into_iter().map(fold_named).collect();
+            match fold_arithmetic(&op, &folded_args) {
+                Some(value) => types::Expression::Constant(value),
+                None => types::Expression::Function(op, folded_args),
+            }
+        }
+        types::Expression::Logic(formula) => types::Expression::Logic(fold_formula(formula)),
+        other => other,
+    }
+}
+
+fn fold_formula(formula: Box<types::Formula>) -> Box<types::Formula> {
+    match *formula {
+        types::Formula::InfixOperator(types::LogicInfixOp::And, left, right) => {
+            let left = fold_formula(left);
+            let right = fold_formula(right);
+            match (&*left, &*right) {
+                (types::Formula::Constant(false), _) | (_, types::Formula::Constant(false)) => {
+                    Box::new(types::Formula::Constant(false))
+                }
+                (types::Formula::Constant(true), _) => right,
+                (_, types::Formula::Constant(true)) => left,
+                _ => Box::new(types::Formula::InfixOperator(types::LogicInfixOp::And, left, right)),
+            }
+        }
+        types::Formula::InfixOperator(types::LogicInfixOp::Or, left, right) => {
+            let left = fold_formula(left);
+            let right = fold_formula(right);
+            match (&*left, &*right) {
+                (types::Formula::Constant(true), _) | (_, types::Formula::Constant(true)) => {
+                    Box::new(types::Formula::Constant(true))
+                }
+                (types::Formula::Constant(false), _) => right,
+                (_, types::Formula::Constant(false)) => left,
+                _ => Box::new(types::Formula::InfixOperator(types::LogicInfixOp::Or, left, right)),
+            }
+        }
+        types::Formula::PrefixOperator(types::LogicPrefixOp::Not, child) => {
+            let child = fold_formula(child);
+            match *child {
+                types::Formula::Constant(b) => Box::new(types::Formula::Constant(!b)),
+                other => {
+                    Box::new(types::Formula::PrefixOperator(types::LogicPrefixOp::Not, Box::new(other)))
+                }
+            }
+        }
+        types::Formula::Predicate(rel, left, right) => {
+            let left = fold_expression(*left);
+            let right = fold_expression(*right);
+            if let (types::Expression::Constant(l), types::Expression::Constant(r)) = (&left, &right) {
+                if let Some(result) = eval_relation(&rel, l, r) {
+                    return Box::new(types::Formula::Constant(result));
+                }
+            }
+            Box::new(types::Formula::Predicate(rel, Box::new(left), Box::new(right)))
+        }
+        other => Box::new(other),
+    }
+}
+
+fn constant_of(named: &types::Named) -> Option<&common::Value> {
+    match named {
+        types::Named::Expression(types::Expression::Constant(value), _) => Some(value),
+        _ => None,
+    }
+}
+
+fn fold_arithmetic(op: &str, args: &[types::Named]) -> Option<common::Value> {
+    if args.len() != 2 {
+        return None;
+    }
+    let left = constant_of(&args[0])?;
+    let right = constant_of(&args[1])?;
+    fold_numeric(op, left, right)
+}
+
+fn fold_numeric(op: &str, left: &common::Value, right: &common::Value) -> Option<common::Value> {
+    match (left, right) {
+        (common::Value::Int(a), common::Value::Int(b)) => {
+            // Leave the node unfolded on overflow (as with divide-by-zero) so a
+            // valid-but-overflowing literal expression never panics at plan time.
+            let value = match op {
+                "Plus" => a.checked_add(*b)?,
+                "Minus" => a.checked_sub(*b)?,
+                "Multiply" => a.checked_mul(*b)?,
+                "Divide" => a.checked_div(*b)?,
+                "Modulo" => a.checked_rem(*b)?,
+                _ => return None,
+            };
+            Some(common::Value::Int(value))
+        }
+        _ => {
+            let a = as_float(left)?;
+            let b = as_float(right)?;
+            let value = match op {
+                "Plus" => a + b,
+                "Minus" => a - b,
+                "Multiply" => a * b,
+                "Divide" if b != 0.0 => a / b,
+                _ => return None,
+            };
+            Some(common::Value::Float(value))
+        }
+    }
+}
+
+fn as_float(value: &common::Value) -> Option<f64> {
+    match value {
+        common::Value::Int(i) => Some(*i as f64),
+        common::Value::Float(f) => Some(*f),
+        _ => None,
+    }
+}
+
+fn eval_relation(rel: &types::Relation, left: &common::Value, right: &common::Value) -> Option<bool> {
+    // Three-valued logic collapsed to false for filtering: any comparison with a
+    // null operand is false.
+    if let common::Value::Null = left {
+        return Some(false);
+    }
+    if let common::Value::Null = right {
+        return Some(false);
+    }
+    match rel {
+        types::Relation::Equal => Some(values_equal(left, right)),
+        types::Relation::NotEqual => Some(!values_equal(left, right)),
+        types::Relation::GreaterEqual => compare_values(left, right).map(|o| o != Ordering::Less),
+        types::Relation::LessEqual => compare_values(left, right).map(|o| o != Ordering::Greater),
+        types::Relation::LessThan => compare_values(left, right).map(|o| o == Ordering::Less),
+        types::Relation::MoreThan => compare_values(left, right).map(|o| o == Ordering::Greater),
+    }
+}
+
+fn values_equal(left: &common::Value, right: &common::Value) -> bool {
+    match (left, right) {
+        (common::Value::Boolean(a), common::Value::Boolean(b)) => a == b,
+        (common::Value::String(a), common::Value::String(b)) => a == b,
+        _ => compare_values(left, right) == Some(Ordering::Equal),
+    }
+}
+
+fn compare_values(left: &common::Value, right: &common::Value) -> Option<Ordering> {
+    match (left, right) {
+        (common::Value::String(a), common::Value::String(b)) => Some(a.cmp(b)),
+        _ => {
+            let a = as_float(left)?;
+            let b = as_float(right)?;
+            a.partial_cmp(&b)
+        }
+    }
 }
 
 #[cfg(test)]
@@ -316,6 +705,7 @@ mod test {
         let before = ast::SelectExpression::Expression(
             Box::new(ast::Expression::Value(Box::new(ast::ValueExpression::FuncCall(
                 "avg".to_string(),
+                false,
                 vec![ast::SelectExpression::Expression(
                     Box::new(ast::Expression::Value(Box::new(ast::ValueExpression::Column(
                         "a".to_string(),
@@ -328,7 +718,7 @@ mod test {
         );
 
         let named = types::Named::Expression(types::Expression::Variable("a".to_string()), Some("a".to_string()));
-        let expected = types::NamedAggregate::new(types::Aggregate::Avg(named), None);
+        let expected = types::NamedAggregate::new(types::Aggregate::Avg(named, false), None);
 
         let ans = parse_aggregate(&before).unwrap();
         assert_eq!(expected, ans);
@@ -400,12 +790,294 @@ mod test {
         assert_eq!(expected, ans);
     }
 
+    #[test]
+    fn test_fold_formula_and_with_false() {
+        // `x AND false` collapses to `false` regardless of the variable operand.
+        let formula = Box::new(types::Formula::InfixOperator(
+            types::LogicInfixOp::And,
+            Box::new(types::Formula::Predicate(
+                types::Relation::Equal,
+                Box::new(types::Expression::Variable("a".to_string())),
+                Box::new(types::Expression::Constant(common::Value::Int(1))),
+            )),
+            Box::new(types::Formula::Constant(false)),
+        ));
+
+        let ans = fold_formula(formula);
+        assert_eq!(Box::new(types::Formula::Constant(false)), ans);
+    }
+
+    #[test]
+    fn test_fold_expression_constant_arithmetic() {
+        // A fully constant arithmetic subtree folds to a single constant.
+        let expr = types::Expression::Function(
+            "Plus".to_string(),
+            vec![
+                types::Named::Expression(types::Expression::Constant(common::Value::Int(1)), None),
+                types::Named::Expression(types::Expression::Constant(common::Value::Int(2)), None),
+            ],
+        );
+
+        let ans = fold_expression(expr);
+        assert_eq!(types::Expression::Constant(common::Value::Int(3)), ans);
+    }
+
+    #[test]
+    fn test_parse_coalesce_lowers_to_case() {
+        // COALESCE(a, b) becomes CASE WHEN a IS NOT NULL THEN a ELSE b END.
+        let before = ast::ValueExpression::FuncCall(
+            "coalesce".to_string(),
+            false,
+            vec![
+                ast::SelectExpression::Expression(
+                    Box::new(ast::Expression::Value(Box::new(ast::ValueExpression::Column("a".to_string())))),
+                    None,
+                ),
+                ast::SelectExpression::Expression(
+                    Box::new(ast::Expression::Value(Box::new(ast::ValueExpression::Column("b".to_string())))),
+                    None,
+                ),
+            ],
+            None,
+        );
+
+        let expected = Box::new(types::Expression::Case(
+            vec![(
+                Box::new(types::Formula::IsNull(
+                    Box::new(types::Expression::Variable("a".to_string())),
+                    true,
+                )),
+                Box::new(types::Expression::Variable("a".to_string())),
+            )],
+            Some(Box::new(types::Expression::Variable("b".to_string()))),
+        ));
+
+        let ans = parse_value_expression(&before).unwrap();
+        assert_eq!(expected, ans);
+    }
+
+    #[test]
+    fn test_parse_query_reports_all_select_errors() {
+        // Two malformed select expressions surface as two tagged diagnostics in a
+        // single pass rather than failing on the first.
+        let bad = || {
+            ast::SelectExpression::Expression(
+                Box::new(ast::Expression::Value(Box::new(ast::ValueExpression::FuncCall(
+                    "coalesce".to_string(),
+                    false,
+                    vec![ast::SelectExpression::Star],
+                    None,
+                )))),
+                None,
+            )
+        };
+
+        let before = ast::SelectStatement::new(vec![bad(), bad()], "elb", None, None, None, None);
+
+        match parse_query(before, common::DataSource::Stdin) {
+            Err(ParseError::Diagnostics(diagnostics)) => {
+                assert_eq!(2, diagnostics.len());
+                assert_eq!(Clause::Select(0), diagnostics[0].clause);
+                assert_eq!(Clause::Select(1), diagnostics[1].clause);
+            }
+            other => panic!("expected two diagnostics, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_query_with_order_by() {
+        let select_exprs = vec![ast::SelectExpression::Expression(
+            Box::new(ast::Expression::Value(Box::new(ast::ValueExpression::Column("a".to_string())))),
+            None,
+        )];
+        let order_by_expr = ast::OrderByExpression::new(vec![(
+            ast::ValueExpression::Column("a".to_string()),
+            ast::SortDirection::Desc,
+        )]);
+
+        let before = ast::SelectStatement::new(select_exprs, "elb", None, None, Some(order_by_expr), None);
+
+        let expected = types::Node::OrderBy(
+            vec![(
+                types::Expression::Variable("a".to_string()),
+                types::SortDirection::Desc,
+                types::NullsOrder::First,
+            )],
+            Box::new(types::Node::Map(
+                vec![types::Named::Expression(
+                    types::Expression::Variable("a".to_string()),
+                    Some("a".to_string()),
+                )],
+                Box::new(types::Node::DataSource(common::DataSource::Stdin)),
+            )),
+        );
+
+        let ans = parse_query(before, common::DataSource::Stdin).unwrap();
+        assert_eq!(expected, ans);
+    }
+
+    #[test]
+    fn test_parse_query_with_having() {
+        // avg(a) GROUP BY b HAVING a > 1 — HAVING must plan as a Filter-like node
+        // sitting directly above the GroupBy it refines.
+        let select_exprs = vec![ast::SelectExpression::Expression(
+            Box::new(ast::Expression::Value(Box::new(ast::ValueExpression::FuncCall(
+                "avg".to_string(),
+                false,
+                vec![ast::SelectExpression::Expression(
+                    Box::new(ast::Expression::Value(Box::new(ast::ValueExpression::Column("a".to_string())))),
+                    None,
+                )],
+                None,
+            )))),
+            None,
+        )];
+        let group_by_expr = ast::GroupByExpression::new(vec!["b".to_string()]);
+        let having_expr = ast::HavingExpression::new(ast::Expression::Condition(
+            ast::Condition::ComparisonExpression(
+                ast::RelationOperator::MoreThan,
+                Box::new(ast::ValueExpression::Column("a".to_string())),
+                Box::new(ast::ValueExpression::Value(ast::Value::Integral(1))),
+            ),
+        ));
+
+        let mut before = ast::SelectStatement::new(select_exprs, "elb", None, Some(group_by_expr), None, None);
+        before.having_expr_opt = Some(having_expr);
+
+        match parse_query(before, common::DataSource::Stdin).unwrap() {
+            types::Node::Having(_, _, child) => match *child {
+                types::Node::GroupBy(..) => {}
+                other => panic!("expected Having above GroupBy, got {:?}", other),
+            },
+            other => panic!("expected Having as the root node, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_case_expression() {
+        // CASE WHEN true THEN 1 ELSE 2 END lowers to a Case with one constant
+        // branch and a constant else.
+        let before = ast::ValueExpression::Case(
+            vec![(
+                ast::Expression::Value(Box::new(ast::ValueExpression::Value(ast::Value::Boolean(true)))),
+                ast::ValueExpression::Value(ast::Value::Integral(1)),
+            )],
+            Some(Box::new(ast::ValueExpression::Value(ast::Value::Integral(2)))),
+        );
+
+        let expected = Box::new(types::Expression::Case(
+            vec![(
+                Box::new(types::Formula::Constant(true)),
+                Box::new(types::Expression::Constant(common::Value::Int(1))),
+            )],
+            Some(Box::new(types::Expression::Constant(common::Value::Int(2)))),
+        ));
+
+        let ans = parse_value_expression(&before).unwrap();
+        assert_eq!(expected, ans);
+    }
+
+    #[test]
+    fn test_parse_aggregate_variants() {
+        // count(*) counts rows.
+        let count_star = ast::SelectExpression::Expression(
+            Box::new(ast::Expression::Value(Box::new(ast::ValueExpression::FuncCall(
+                "count".to_string(),
+                false,
+                vec![ast::SelectExpression::Star],
+                None,
+            )))),
+            None,
+        );
+        assert_eq!(
+            types::NamedAggregate::new(types::Aggregate::Count(types::Named::Star, false), None),
+            parse_aggregate(&count_star).unwrap()
+        );
+
+        // count(distinct a).
+        let count_distinct = ast::SelectExpression::Expression(
+            Box::new(ast::Expression::Value(Box::new(ast::ValueExpression::FuncCall(
+                "count".to_string(),
+                true,
+                vec![ast::SelectExpression::Expression(
+                    Box::new(ast::Expression::Value(Box::new(ast::ValueExpression::Column("a".to_string())))),
+                    None,
+                )],
+                None,
+            )))),
+            None,
+        );
+        let named = types::Named::Expression(types::Expression::Variable("a".to_string()), Some("a".to_string()));
+        assert_eq!(
+            types::NamedAggregate::new(types::Aggregate::Count(named, true), None),
+            parse_aggregate(&count_distinct).unwrap()
+        );
+
+        // group_concat(a, ', ') carries its separator.
+        let group_concat = ast::SelectExpression::Expression(
+            Box::new(ast::Expression::Value(Box::new(ast::ValueExpression::FuncCall(
+                "group_concat".to_string(),
+                false,
+                vec![
+                    ast::SelectExpression::Expression(
+                        Box::new(ast::Expression::Value(Box::new(ast::ValueExpression::Column("a".to_string())))),
+                        None,
+                    ),
+                    ast::SelectExpression::Expression(
+                        Box::new(ast::Expression::Value(Box::new(ast::ValueExpression::Value(
+                            ast::Value::StringLiteral(", ".to_string()),
+                        )))),
+                        None,
+                    ),
+                ],
+                None,
+            )))),
+            None,
+        );
+        let named = types::Named::Expression(types::Expression::Variable("a".to_string()), Some("a".to_string()));
+        assert_eq!(
+            types::NamedAggregate::new(
+                types::Aggregate::GroupConcat(named, false, Some(", ".to_string())),
+                None
+            ),
+            parse_aggregate(&group_concat).unwrap()
+        );
+
+        // DISTINCT * is rejected for everything but count.
+        let sum_distinct_star = ast::SelectExpression::Expression(
+            Box::new(ast::Expression::Value(Box::new(ast::ValueExpression::FuncCall(
+                "sum".to_string(),
+                true,
+                vec![ast::SelectExpression::Star],
+                None,
+            )))),
+            None,
+        );
+        assert_eq!(
+            Err(ParseError::DistinctStarNotAllowed),
+            parse_aggregate(&sum_distinct_star)
+        );
+
+        // sum(*) aggregates every column, which is a type error.
+        let sum_star = ast::SelectExpression::Expression(
+            Box::new(ast::Expression::Value(Box::new(ast::ValueExpression::FuncCall(
+                "sum".to_string(),
+                false,
+                vec![ast::SelectExpression::Star],
+                None,
+            )))),
+            None,
+        );
+        assert_eq!(Err(ParseError::TypeMismatch), parse_aggregate(&sum_star));
+    }
+
     #[test]
     fn test_parse_query_with_group_by() {
         let select_exprs = vec![
             ast::SelectExpression::Expression(
                 Box::new(ast::Expression::Value(Box::new(ast::ValueExpression::FuncCall(
                     "avg".to_string(),
+                    false,
                     vec![ast::SelectExpression::Expression(
                         Box::new(ast::Expression::Value(Box::new(ast::ValueExpression::Column(
                             "a".to_string(),
@@ -419,6 +1091,7 @@ mod test {
             ast::SelectExpression::Expression(
                 Box::new(ast::Expression::Value(Box::new(ast::ValueExpression::FuncCall(
                     "count".to_string(),
+                    false,
                     vec![ast::SelectExpression::Expression(
                         Box::new(ast::Expression::Value(Box::new(ast::ValueExpression::Column(
                             "b".to_string(),
@@ -460,17 +1133,17 @@ mod test {
 
         let named_aggregates = vec![
             types::NamedAggregate::new(
-                types::Aggregate::Avg(types::Named::Expression(
-                    types::Expression::Variable("a".to_string()),
-                    Some("a".to_string()),
-                )),
+                types::Aggregate::Avg(
+                    types::Named::Expression(types::Expression::Variable("a".to_string()), Some("a".to_string())),
+                    false,
+                ),
                 None,
             ),
             types::NamedAggregate::new(
-                types::Aggregate::Count(types::Named::Expression(
-                    types::Expression::Variable("b".to_string()),
-                    Some("b".to_string()),
-                )),
+                types::Aggregate::Count(
+                    types::Named::Expression(types::Expression::Variable("b".to_string()), Some("b".to_string())),
+                    false,
+                ),
                 None,
             ),
         ];